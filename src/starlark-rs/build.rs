@@ -2,22 +2,381 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-// TODO: compile this with ninja instead.
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+
+/// Environment variables this build script reads. Mirrors the
+/// `rusty_v8`-style "toolchain via env" convention: every knob a user might
+/// need to override a source build lives here, and every one of them is
+/// registered with `cargo:rerun-if-env-changed` below so changing it
+/// actually triggers a rebuild instead of silently reusing stale output.
+const TRACKED_ENV: &[&str] = &[
+    "STARGN_FROM_SOURCE",
+    "STARGN_ARCHIVE",
+    "STARGN_ARCHIVE_BASE",
+    "STARGN_ARCHIVE_GN",
+    "STARGN_MIRROR",
+    "STARGN_STATIC",
+    "GN",
+    "NINJA",
+    "GN_ARGS",
+    "CLANG_BASE_PATH",
+    "SCCACHE",
+    "CCACHE",
+    "OUT_DIR",
+    "TARGET",
+    "HOST",
+    "PROFILE",
+];
+
+/// Default host for prebuilt `base`/`gn` archives, overridable via
+/// `STARGN_MIRROR` for offline/vendored mirrors.
+const DEFAULT_MIRROR: &str = "https://github.com/TritiumXs/gn/releases/download";
+
+/// Name of the prebuilt tarball for this crate version, target and profile,
+/// e.g. `stargn-0.1.0-x86_64-unknown-linux-gnu-release.tar.gz`.
+fn archive_name(target: &str, profile: &str) -> String {
+    let version = env::var("CARGO_PKG_VERSION").unwrap_or_else(|_| "0.0.0".into());
+    format!("stargn-{version}-{target}-{profile}.tar.gz")
+}
+
+/// Fetch (or reuse local `STARGN_ARCHIVE*` files) the prebuilt `base`/`gn`
+/// archive, verify it, and unpack it into `out_dir`. Returns the directory
+/// `libbase.a`/`libgn.a` were staged into.
+///
+/// A combined `STARGN_ARCHIVE` tarball is expected to already contain both
+/// libraries under their expected names. A bare `.a` is only meaningful
+/// for a single library, so that case takes two separate variables instead
+/// — `STARGN_ARCHIVE_BASE` and `STARGN_ARCHIVE_GN` — one per library, each
+/// staged (and renamed, if needed) to its expected `lib*.a` name. Either
+/// way, we verify both libraries actually landed in `out_dir` before
+/// returning, so a half-supplied archive fails with a clear error instead
+/// of an opaque link failure later.
+fn fetch_prebuilt_archive(out_dir: &Path) -> miette::Result<PathBuf> {
+    let target = env::var("TARGET").unwrap_or_default();
+    let profile = env::var("PROFILE").unwrap_or_else(|_| "debug".into());
+    let name = archive_name(&target, &profile);
+
+    let unpack_dir = out_dir.join("stargn_prebuilt");
+    std::fs::create_dir_all(&unpack_dir).map_err(|e| miette::miette!("{e}"))?;
+
+    let base_override = env::var_os("STARGN_ARCHIVE_BASE");
+    let gn_override = env::var_os("STARGN_ARCHIVE_GN");
+
+    if base_override.is_some() || gn_override.is_some() {
+        let base = base_override.ok_or_else(|| {
+            miette::miette!("STARGN_ARCHIVE_GN is set but STARGN_ARCHIVE_BASE is not; both are required so each library has a file to stage from")
+        })?;
+        let gn = gn_override.ok_or_else(|| {
+            miette::miette!("STARGN_ARCHIVE_BASE is set but STARGN_ARCHIVE_GN is not; both are required so each library has a file to stage from")
+        })?;
+        stage_single_library(Path::new(&base), &unpack_dir, "libbase.a")?;
+        stage_single_library(Path::new(&gn), &unpack_dir, "libgn.a")?;
+    } else {
+        let archive_path = match env::var_os("STARGN_ARCHIVE") {
+            Some(local) => {
+                let local = PathBuf::from(local);
+                if !local.exists() {
+                    return Err(miette::miette!(
+                        "STARGN_ARCHIVE points at {local:?}, which does not exist"
+                    ));
+                }
+                local
+            }
+            None => {
+                let mirror = env::var("STARGN_MIRROR").unwrap_or_else(|_| DEFAULT_MIRROR.into());
+                let url = format!("{mirror}/{name}");
+                let dest = out_dir.join(&name);
+                download(&url, &dest)?;
+                verify_sha256(&dest, &format!("{url}.sha256"))?;
+                dest
+            }
+        };
+        if archive_path.extension().is_some_and(|e| e == "a") {
+            let file_name = archive_path.file_name().unwrap_or_default();
+            std::fs::copy(&archive_path, unpack_dir.join(file_name))
+                .map_err(|e| miette::miette!("{e}"))?;
+        } else {
+            stage_tarball(&archive_path, &unpack_dir)?;
+        }
+    }
+
+    for expected in ["libbase.a", "libgn.a"] {
+        if !unpack_dir.join(expected).exists() {
+            return Err(miette::miette!(
+                "{expected} is still missing from {} after staging; a bare \
+                 `.a` STARGN_ARCHIVE only covers one library at a time — set \
+                 STARGN_ARCHIVE_BASE and STARGN_ARCHIVE_GN instead, or supply \
+                 a combined .tar/.tar.gz containing both",
+                unpack_dir.display()
+            ));
+        }
+    }
+    Ok(unpack_dir)
+}
+
+/// Stages a single library file (`.a`, or a `.tar`/`.tar.gz` containing
+/// just that one `.a`) into `unpack_dir` under `expected_name`.
+fn stage_single_library(
+    archive: &Path,
+    unpack_dir: &Path,
+    expected_name: &str,
+) -> miette::Result<()> {
+    let name = archive
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    if name.ends_with(".a") {
+        std::fs::copy(archive, unpack_dir.join(expected_name)).map_err(|e| miette::miette!("{e}"))?;
+        Ok(())
+    } else {
+        stage_tarball(archive, unpack_dir)
+    }
+}
+
+/// Un-tars `archive` into `unpack_dir`, gzip-decoding first for
+/// `.tar.gz`/`.tgz` and reading the bytes as-is for a bare `.tar`.
+fn stage_tarball(archive: &Path, unpack_dir: &Path) -> miette::Result<()> {
+    let name = archive
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        let f = File::open(archive).map_err(|e| miette::miette!("{e}"))?;
+        let decompressed = flate2::read::GzDecoder::new(f);
+        tar::Archive::new(decompressed)
+            .unpack(unpack_dir)
+            .map_err(|e| miette::miette!("failed to unpack {}: {e}", archive.display()))
+    } else if name.ends_with(".tar") {
+        let f = File::open(archive).map_err(|e| miette::miette!("{e}"))?;
+        tar::Archive::new(f)
+            .unpack(unpack_dir)
+            .map_err(|e| miette::miette!("failed to unpack {}: {e}", archive.display()))
+    } else {
+        Err(miette::miette!(
+            "don't know how to stage archive {}: expected a .a, .tar, .tar.gz or .tgz file",
+            archive.display()
+        ))
+    }
+}
+
+/// Download `url` to `dest`, overwriting any existing file.
+fn download(url: &str, dest: &Path) -> miette::Result<()> {
+    let resp = ureq::get(url)
+        .call()
+        .map_err(|e| miette::miette!("failed to download {url}: {e}"))?;
+    let mut body = Vec::new();
+    resp.into_reader()
+        .read_to_end(&mut body)
+        .map_err(|e| miette::miette!("{e}"))?;
+    let mut f = File::create(dest).map_err(|e| miette::miette!("{e}"))?;
+    f.write_all(&body).map_err(|e| miette::miette!("{e}"))?;
+    Ok(())
+}
+
+/// Fetch the `<archive>.sha256` sidecar published alongside the tarball and
+/// confirm it matches the bytes we downloaded.
+fn verify_sha256(archive: &Path, sha256_url: &str) -> miette::Result<()> {
+    let resp = ureq::get(sha256_url)
+        .call()
+        .map_err(|e| miette::miette!("failed to download {sha256_url}: {e}"))?;
+    let expected = resp
+        .into_string()
+        .map_err(|e| miette::miette!("{e}"))?
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let mut hasher = Sha256::new();
+    let mut f = File::open(archive).map_err(|e| miette::miette!("{e}"))?;
+    std::io::copy(&mut f, &mut hasher).map_err(|e| miette::miette!("{e}"))?;
+    let actual = format!("{:x}", hasher.finalize());
+
+    if actual != expected {
+        return Err(miette::miette!(
+            "sha256 mismatch for {}: expected {expected}, got {actual}",
+            archive.display()
+        ));
+    }
+    Ok(())
+}
+
+/// `cargo doc` (docs.rs) and IDE indexing (rust-analyzer's RLS-compatible
+/// driver) both invoke this build script without a C++ toolchain or a
+/// prebuilt `out` directory available. Detect both cases so we can skip the
+/// autocxx/ninja work entirely and let the crate "build" as an empty shell.
+fn skip_cxx_build() -> bool {
+    if env::var_os("DOCS_RS").is_some() {
+        return true;
+    }
+    // rust-analyzer (and the older RLS) invoke `cargo check` through a
+    // wrapper binary so they can intercept diagnostics; that wrapper's path
+    // ends up in `CARGO`.
+    if let Some(cargo) = env::var_os("CARGO") {
+        let name = Path::new(&cargo)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        if name.contains("rust-analyzer") || name.contains("rls") {
+            return true;
+        }
+    }
+    false
+}
+
+/// Locate an external tool, preferring an explicit env var override and
+/// falling back to the name being on `PATH`.
+fn find_tool(env_var: &str, default_name: &str) -> PathBuf {
+    env::var_os(env_var)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(default_name))
+}
+
+/// Run GN's bootstrap (`gn gen`) followed by `ninja` so the `base` and `gn`
+/// static libraries exist before we hand the result to autocxx. This is the
+/// "build from source" counterpart to the prebuilt-archive path.
+fn build_from_source(out_dir: &Path) -> miette::Result<PathBuf> {
+    let gn = find_tool("GN", "gn");
+    let ninja = find_tool("NINJA", "ninja");
+    let profile = env::var("PROFILE").unwrap_or_else(|_| "debug".into());
+    let is_release = profile == "release";
+
+    let build_dir = out_dir.join("gn_out");
+    std::fs::create_dir_all(&build_dir).map_err(|e| miette::miette!("{e}"))?;
+
+    let mut gn_args = if is_release {
+        String::from("is_debug=false ")
+    } else {
+        String::from("is_debug=true ")
+    };
+    if let Ok(clang_base_path) = env::var("CLANG_BASE_PATH") {
+        gn_args.push_str(&format!("clang_base_path=\"{clang_base_path}\" "));
+    }
+    if let Some(cc_wrapper) = env::var_os("SCCACHE").or_else(|| env::var_os("CCACHE")) {
+        gn_args.push_str(&format!(
+            "cc_wrapper=\"{}\" ",
+            cc_wrapper.to_string_lossy()
+        ));
+    }
+    if let Ok(extra) = env::var("GN_ARGS") {
+        gn_args.push_str(&extra);
+    }
+
+    run(Command::new(&gn)
+        .arg("gen")
+        .arg(&build_dir)
+        .arg(format!("--args={gn_args}")))?;
+    run(Command::new(&ninja)
+        .arg("-C")
+        .arg(&build_dir)
+        .arg("base")
+        .arg("gn"))?;
+
+    Ok(build_dir)
+}
+
+fn run(cmd: &mut Command) -> miette::Result<()> {
+    let status = cmd.status().map_err(|e| miette::miette!("{e}"))?;
+    if !status.success() {
+        return Err(miette::miette!("command failed: {:?} ({status})", cmd));
+    }
+    Ok(())
+}
+
+/// Whether `base`/`gn` should be linked statically for `target`. Mirrors
+/// BoringSSL's `static=crypto`/`static=ssl` split: desktop targets dynamic
+/// link against a system-wide `out` by default, but mobile targets (where
+/// there's no "system" copy to share) always link statically.
+fn static_link(target: &str) -> bool {
+    target.contains("android") || target.contains("ios") || env::var_os("STARGN_STATIC").is_some()
+}
+
+/// Extra clang flags needed to cross-compile the C++ side for `target`
+/// when it differs from `host`: an explicit `--target` triple, and for
+/// Apple targets an `-isysroot` pointing at the platform SDK (read from
+/// `CLANG_BASE_PATH`, matching the source-build path above).
+fn cross_compile_flags(target: &str, host: &str) -> Vec<String> {
+    if target == host {
+        return Vec::new();
+    }
+    let mut flags = vec![format!("--target={target}")];
+    if target.contains("apple") {
+        if let Ok(clang_base_path) = env::var("CLANG_BASE_PATH") {
+            flags.push(format!("-isysroot{clang_base_path}"));
+        }
+    }
+    flags
+}
+
+/// Picks the directory `base`/`gn` archives live in for `target`: a
+/// per-triple `out/<triple>` directory if the user has pre-staged one
+/// (the way Android/arm or iOS cross builds typically do), falling back
+/// to the plain `out` directory used by host builds.
+fn local_out_dir(target: &str) -> PathBuf {
+    let per_target = std::path::PathBuf::from("../../out").join(target);
+    if per_target.join("libbase.a").exists() && per_target.join("libgn.a").exists() {
+        per_target
+    } else {
+        std::path::PathBuf::from("../../out")
+    }
+}
+
 fn main() -> miette::Result<()> {
+    for var in TRACKED_ENV {
+        println!("cargo:rerun-if-env-changed={var}");
+    }
+
+    if skip_cxx_build() {
+        return Ok(());
+    }
+
     let here = std::path::PathBuf::from(".");
     let src = std::path::PathBuf::from("../");
-    let out = std::path::PathBuf::from("../../out");
+
+    let target = env::var("TARGET").unwrap_or_default();
+    let host = env::var("HOST").unwrap_or_default();
+
+    let local_out = local_out_dir(&target);
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let out = if env::var_os("STARGN_FROM_SOURCE").is_some() {
+        build_from_source(&out_dir)?
+    } else if local_out.join("libbase.a").exists() && local_out.join("libgn.a").exists() {
+        local_out
+    } else {
+        fetch_prebuilt_archive(&out_dir)?
+    };
+
+    let mut clang_args = vec!["-std=c++17".to_string()];
+    clang_args.extend(cross_compile_flags(&target, &host));
+    let clang_arg_refs: Vec<&str> = clang_args.iter().map(String::as_str).collect();
+
     let mut b = autocxx_build::Builder::new("stargn_main.rs", &[&here, &src, &out])
-        .extra_clang_args(&["-std=c++17"])
+        .extra_clang_args(&clang_arg_refs)
         .build()?;
     b.file("stargn_main.h").file("stargn_main.cc").file("starlark_glue.h").file("starlark_glue.cc")
+        .file("rust_target.h").file("rust_target.cc")
         .flag_if_supported("-Wno-unused-parameter")
         .flag_if_supported("-std=c++17")
         .compile("stargn");
     println!("cargo:rerun-if-changed=stargn_main.rs");
+    println!("cargo:rerun-if-changed=mode.rs");
+    println!("cargo:rerun-if-changed=generate.rs");
+    println!("cargo:rerun-if-changed=remap_alloc.rs");
     println!("cargo:rerun-if-changed=starlark_glue.cc");
-    println!("cargo:rustc-link-search=../../out");
-    println!("cargo:rustc-link-lib=base");
-    println!("cargo:rustc-link-lib=gn");
+    println!("cargo:rerun-if-changed=starlark_glue.h");
+    println!("cargo:rerun-if-changed=rust_target.cc");
+    println!("cargo:rerun-if-changed=rust_target.h");
+    println!("cargo:rustc-link-search={}", out.display());
+    let link_kind = if static_link(&target) { "static" } else { "dylib" };
+    println!("cargo:rustc-link-lib={link_kind}=base");
+    println!("cargo:rustc-link-lib={link_kind}=gn");
     Ok(())
-}
\ No newline at end of file
+}
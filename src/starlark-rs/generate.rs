@@ -0,0 +1,248 @@
+// Copyright 2023 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Generates GN (or Starlark) build files from a `Cargo.toml` + lockfile,
+//! the way Chromium's `gnrt` does for `third_party/rust`. Each resolved
+//! crate in the dependency graph becomes a `rust_static_library`-style
+//! target so a Rust crate tree can be built by GN/ninja without hand
+//! maintaining build files.
+
+use std::path::{Path, PathBuf};
+
+use crate::mode::BuildMode;
+
+/// One resolved crate, ready to be lowered into a GN or Starlark target.
+pub struct CrateTarget {
+    /// GN target name, e.g. `serde-1.0.188`.
+    pub name: String,
+    /// Path to `lib.rs` (or the `[lib]` path override), as the absolute
+    /// path `cargo metadata` reports for the `[lib]` target.
+    pub crate_root: PathBuf,
+    /// Every `.rs` file GN needs to list as a source so incremental
+    /// rebuilds and `gn check` see the whole crate.
+    pub sources: Vec<PathBuf>,
+    /// Rust edition, passed through as `edition = "..."`.
+    pub edition: String,
+    /// Cargo features enabled for this resolution of the crate.
+    pub features: Vec<String>,
+    /// GN target labels of this crate's dependencies.
+    pub deps: Vec<String>,
+}
+
+/// Parses `Cargo.toml` and its lockfile, resolves the dependency graph and
+/// per-crate features, and returns one [`CrateTarget`] per resolved crate.
+///
+/// This mirrors `cargo metadata`'s resolve graph rather than reimplementing
+/// dependency resolution: `manifest_path` is handed to `cargo metadata
+/// --locked` and the JSON result is walked into `CrateTarget`s.
+pub fn resolve(manifest_path: impl AsRef<Path>) -> miette::Result<Vec<CrateTarget>> {
+    let manifest_path = manifest_path.as_ref();
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(manifest_path)
+        .other_options(["--locked".to_string()])
+        .exec()
+        .map_err(|e| miette::miette!("cargo metadata failed: {e}"))?;
+
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or_else(|| miette::miette!("cargo metadata returned no resolve graph"))?;
+
+    let mut targets = Vec::with_capacity(resolve.nodes.len());
+    for node in &resolve.nodes {
+        let package = metadata
+            .packages
+            .iter()
+            .find(|p| p.id == node.id)
+            .ok_or_else(|| miette::miette!("resolve node {} missing from packages", node.id))?;
+
+        let crate_root = package
+            .targets
+            .iter()
+            .find(|t| t.kind.iter().any(|k| k == "lib"))
+            .map(|t| t.src_path.clone().into_std_path_buf())
+            .ok_or_else(|| miette::miette!("{} has no [lib] target", package.name))?;
+
+        let sources = collect_sources(crate_root.parent().unwrap_or(Path::new(".")))?;
+
+        let deps = node
+            .deps
+            .iter()
+            // `node.dependencies` merges normal, build and dev deps with no
+            // kind info; `node.deps` carries `dep_kinds` so we can keep
+            // only the ones that are actually link deps. Dev-deps routinely
+            // cycle back to this crate itself (integration tests), and
+            // build-deps aren't linked into the target at all.
+            .filter(|dep| {
+                dep.dep_kinds.is_empty()
+                    || dep
+                        .dep_kinds
+                        .iter()
+                        .any(|k| k.kind == cargo_metadata::DependencyKind::Normal)
+            })
+            .filter_map(|dep| metadata.packages.iter().find(|p| p.id == dep.pkg))
+            .map(|dep| gn_target_name(&dep.name, &dep.version.to_string()))
+            .collect();
+
+        targets.push(CrateTarget {
+            name: gn_target_name(&package.name, &package.version.to_string()),
+            crate_root,
+            sources,
+            edition: package.edition.to_string(),
+            features: node.features.clone(),
+            deps,
+        });
+    }
+    Ok(targets)
+}
+
+/// Joins the crate name and version with `-`, so `serde 1.0.188` becomes
+/// `serde-1.0.188`; the dots of the version are kept.
+fn gn_target_name(name: &str, version: &str) -> String {
+    format!("{name}-{version}")
+}
+
+/// Walk a crate's source directory for every `.rs` file GN needs as a
+/// `sources` entry.
+fn collect_sources(crate_dir: &Path) -> miette::Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for entry in walkdir::WalkDir::new(crate_dir) {
+        let entry = entry.map_err(|e| miette::miette!("{e}"))?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|e| e == "rs") {
+            sources.push(entry.path().to_path_buf());
+        }
+    }
+    Ok(sources)
+}
+
+/// Top-level entry point: resolve `manifest_path`'s dependency graph and
+/// write one build file per crate into `out_dir`, in `mode`'s syntax.
+/// Returns the paths written, one per resolved crate.
+pub fn generate(
+    manifest_path: impl AsRef<Path>,
+    mode: BuildMode,
+    out_dir: impl AsRef<Path>,
+) -> miette::Result<Vec<PathBuf>> {
+    let out_dir = out_dir.as_ref();
+    resolve(manifest_path)?
+        .iter()
+        .map(|target| emit(target, mode, &out_dir.join(&target.name)))
+        .collect()
+}
+
+/// Emits one `CrateTarget` as either a native `BUILD.gn` target or a
+/// Starlark `rust_static_library` call, selected by
+/// `mode` so the same resolved graph can feed either front end.
+pub fn emit(target: &CrateTarget, mode: BuildMode, out_dir: &Path) -> miette::Result<PathBuf> {
+    let (file_name, body) = match mode {
+        BuildMode::NativeGn => ("BUILD.gn", emit_gn(target)),
+        BuildMode::Starlark => ("BUILD.star", emit_starlark(target)),
+    };
+    std::fs::create_dir_all(out_dir).map_err(|e| miette::miette!("{e}"))?;
+    let path = out_dir.join(file_name);
+    std::fs::write(&path, body).map_err(|e| miette::miette!("{e}"))?;
+    Ok(path)
+}
+
+fn emit_gn(target: &CrateTarget) -> String {
+    let sources = join_quoted(&displayable_paths(&target.sources));
+    let features = join_quoted(&target.features);
+    let deps = join_quoted(&target.deps);
+    format!(
+        "rust_static_library(\"{name}\") {{\n  crate_root = \"{crate_root}\"\n  sources = [{sources}]\n  edition = \"{edition}\"\n  features = [{features}]\n  deps = [{deps}]\n}}\n",
+        name = escape_string_literal(&target.name),
+        crate_root = escape_string_literal(&target.crate_root.display().to_string()),
+        edition = escape_string_literal(&target.edition),
+    )
+}
+
+fn emit_starlark(target: &CrateTarget) -> String {
+    let sources = join_quoted(&displayable_paths(&target.sources));
+    let features = join_quoted(&target.features);
+    let deps = join_quoted(&target.deps);
+    format!(
+        "rust_static_library(\n    name = \"{name}\",\n    crate_root = \"{crate_root}\",\n    sources = [{sources}],\n    edition = \"{edition}\",\n    features = [{features}],\n    deps = [{deps}],\n)\n",
+        name = escape_string_literal(&target.name),
+        crate_root = escape_string_literal(&target.crate_root.display().to_string()),
+        edition = escape_string_literal(&target.edition),
+    )
+}
+
+/// `Path`/`PathBuf` don't implement `Display`, so `join_quoted` (which
+/// needs `Display` to stringify and escape each item) can't take
+/// `&[PathBuf]` directly; render each path first.
+fn displayable_paths(paths: &[PathBuf]) -> Vec<String> {
+    paths.iter().map(|p| p.display().to_string()).collect()
+}
+
+fn join_quoted<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| format!("\"{}\"", escape_string_literal(&i.to_string())))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Escapes `\` and `"` so a value (e.g. an absolute `crate_root` path, a
+/// crate name, a feature) can't break out of a GN/Starlark string literal.
+/// Both languages share C-style string escaping for these two characters.
+fn escape_string_literal(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gn_target_name_joins_name_and_version() {
+        assert_eq!(gn_target_name("serde", "1.0.188"), "serde-1.0.188");
+    }
+
+    #[test]
+    fn join_quoted_quotes_each_item() {
+        assert_eq!(
+            join_quoted(&["foo".to_string(), "bar".to_string()]),
+            "\"foo\", \"bar\""
+        );
+    }
+
+    #[test]
+    fn join_quoted_escapes_quotes_and_backslashes() {
+        assert_eq!(
+            join_quoted(&["C:\\path\\to\"thing\"".to_string()]),
+            "\"C:\\\\path\\\\to\\\"thing\\\"\""
+        );
+    }
+
+    fn sample_target() -> CrateTarget {
+        CrateTarget {
+            name: "serde-1.0.188".to_string(),
+            crate_root: PathBuf::from("/home/user/.cargo/lib.rs"),
+            sources: vec![PathBuf::from("lib.rs"), PathBuf::from("de.rs")],
+            edition: "2018".to_string(),
+            features: vec!["derive".to_string()],
+            deps: vec!["serde_derive-1.0.188".to_string()],
+        }
+    }
+
+    #[test]
+    fn emit_gn_produces_a_rust_static_library_target() {
+        let target = sample_target();
+        let body = emit_gn(&target);
+        assert!(body.starts_with("rust_static_library(\"serde-1.0.188\") {"));
+        assert!(body.contains("edition = \"2018\""));
+        assert!(body.contains("sources = [\"lib.rs\", \"de.rs\"]"));
+        assert!(body.contains("features = [\"derive\"]"));
+        assert!(body.contains("deps = [\"serde_derive-1.0.188\"]"));
+    }
+
+    #[test]
+    fn emit_starlark_produces_a_keyword_call() {
+        let target = sample_target();
+        let body = emit_starlark(&target);
+        assert!(body.starts_with("rust_static_library(\n    name = \"serde-1.0.188\","));
+        assert!(body.contains("edition = \"2018\","));
+    }
+}
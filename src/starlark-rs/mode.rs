@@ -0,0 +1,31 @@
+// Copyright 2023 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Selects which front end parses a directory's build file.
+
+use std::path::Path;
+
+/// Per-directory build file front end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildMode {
+    /// `BUILD.gn`, parsed by GN's native syntax.
+    NativeGn,
+    /// `BUILD.star`, parsed by the Starlark evaluator.
+    Starlark,
+}
+
+impl BuildMode {
+    /// Infer the mode for `dir` from which build file is present, so a
+    /// tree can migrate to Starlark incrementally, directory by directory,
+    /// instead of needing a single global switch.
+    pub fn detect(dir: &Path) -> Option<Self> {
+        if dir.join("BUILD.star").exists() {
+            Some(Self::Starlark)
+        } else if dir.join("BUILD.gn").exists() {
+            Some(Self::NativeGn)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2023 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Routes Rust's global allocator through `base`'s allocator, mirroring
+//! Chromium's `remap_alloc` shim. Without this, a binary that links both a
+//! `base`/`gn` C++ target and Rust `.rlib`s ends up with two independent
+//! allocators, and freeing a pointer across the FFI boundary with the
+//! wrong one is undefined behavior.
+//!
+//! Only compiled in behind the `remap_alloc` feature: `#[global_allocator]`
+//! may appear at most once in a crate graph, so this stays opt-in for the
+//! final binary crate that actually wants it rather than forced on every
+//! consumer of this library.
+
+use std::alloc::{GlobalAlloc, Layout};
+
+extern "C" {
+    fn stargn_base_alloc(size: usize, align: usize) -> *mut u8;
+    fn stargn_base_dealloc(ptr: *mut u8, size: usize, align: usize);
+    fn stargn_base_realloc(
+        ptr: *mut u8,
+        old_size: usize,
+        align: usize,
+        new_size: usize,
+    ) -> *mut u8;
+}
+
+struct RemapAlloc;
+
+unsafe impl GlobalAlloc for RemapAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        stargn_base_alloc(layout.size(), layout.align())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        stargn_base_dealloc(ptr, layout.size(), layout.align())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        stargn_base_realloc(ptr, layout.size(), layout.align(), new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: RemapAlloc = RemapAlloc;
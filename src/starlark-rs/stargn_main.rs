@@ -0,0 +1,78 @@
+// Copyright 2023 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use autocxx::prelude::*;
+
+mod generate;
+mod mode;
+#[cfg(feature = "remap_alloc")]
+mod remap_alloc;
+
+pub use generate::{generate, CrateTarget};
+pub use mode::BuildMode;
+
+include_cpp! {
+    #include "stargn_main.h"
+    #include "starlark_glue.h"
+    #include "rust_target.h"
+    safety!(unsafe_ffi)
+    generate!("stargn::EvalResult")
+    generate!("stargn::EvalStarlarkFile")
+    generate!("stargn::ParseNativeGnFile")
+    generate!("stargn::StdRlib")
+    generate!("stargn::FindStdRlibs")
+}
+
+/// A GN target graph produced by either the native parser or the Starlark
+/// evaluator. Wraps the same `Item` subclasses (`Target`, `Config`, ...)
+/// `libgn` already builds from native `BUILD.gn` files, so ninja
+/// generation downstream is unaffected by which front end produced them.
+pub struct TargetGraph {
+    inner: cxx::UniquePtr<ffi::stargn::EvalResult>,
+}
+
+/// Entry point for evaluating a directory's build file, through either
+/// front end, into GN's target graph.
+pub struct StarGn;
+
+impl StarGn {
+    /// Evaluate `dir`'s build file with whichever front end
+    /// [`BuildMode::detect`] finds present — `BUILD.star` through the
+    /// Starlark evaluator, `BUILD.gn` through GN's native parser — so a
+    /// tree can migrate to Starlark one directory at a time instead of
+    /// needing a single global switch.
+    pub fn eval_dir(dir: impl AsRef<std::path::Path>) -> miette::Result<TargetGraph> {
+        let dir = dir.as_ref();
+        match BuildMode::detect(dir) {
+            Some(mode @ BuildMode::Starlark) => Self::eval_file(dir.join("BUILD.star"), mode),
+            Some(mode @ BuildMode::NativeGn) => Self::eval_file(dir.join("BUILD.gn"), mode),
+            None => Err(miette::miette!(
+                "no BUILD.gn or BUILD.star in {}",
+                dir.display()
+            )),
+        }
+    }
+
+    /// Evaluate `path` as a build file in `mode`'s syntax and return the
+    /// resulting target graph. For [`BuildMode::Starlark`],
+    /// target-declaring builtins (`executable`, `static_library`,
+    /// `source_set`, `config`, `action`) are registered into the Starlark
+    /// environment by `starlark_glue.cc` before evaluation starts; for
+    /// [`BuildMode::NativeGn`] the same builtins run against GN's native
+    /// parser instead, so both modes produce identically-shaped graphs.
+    pub fn eval_file(
+        path: impl AsRef<std::path::Path>,
+        mode: BuildMode,
+    ) -> miette::Result<TargetGraph> {
+        let path = path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| miette::miette!("path is not valid UTF-8"))?;
+        let inner = match mode {
+            BuildMode::Starlark => ffi::stargn::EvalStarlarkFile(&path.into()),
+            BuildMode::NativeGn => ffi::stargn::ParseNativeGnFile(&path.into()),
+        };
+        Ok(TargetGraph { inner })
+    }
+}